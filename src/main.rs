@@ -1,14 +1,354 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::io;
-use std::io::{BufRead, BufReader};
-use std::sync::mpsc::sync_channel;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::sync::mpsc::{sync_channel, SyncSender};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use clap::{AppSettings, Clap};
-use flate2::read::GzDecoder;
+use flate2::read::{GzDecoder, MultiGzDecoder};
+use flate2::{Decompress, FlushDecompress, Status};
 use rayon::prelude::*;
+use regex::Regex;
 use simdjson_rust::dom::element::{Element, ElementType};
 
+/// compression codecs that `zline`/`select` can autodetect and stream through
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Codec {
+    Gzip,
+    Zstd,
+    Lz4,
+    Bzip2,
+    None,
+}
+
+impl Codec {
+    fn from_str(s: &str) -> Result<Codec, &'static str> {
+        match s {
+            "gzip" => Ok(Codec::Gzip),
+            "zstd" => Ok(Codec::Zstd),
+            "lz4" => Ok(Codec::Lz4),
+            "bzip2" => Ok(Codec::Bzip2),
+            "none" => Ok(Codec::None),
+            _ => Err("unknown codec"),
+        }
+    }
+
+    fn from_magic(bytes: &[u8]) -> Option<Codec> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Codec::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Codec::Zstd)
+        } else if bytes.starts_with(&[0x04, 0x22, 0x4d, 0x18]) {
+            Some(Codec::Lz4)
+        } else if bytes.starts_with(&[0x42, 0x5a, 0x68]) {
+            Some(Codec::Bzip2)
+        } else {
+            None
+        }
+    }
+
+    fn from_extension(fname: &str) -> Codec {
+        if fname.ends_with(".gz") {
+            Codec::Gzip
+        } else if fname.ends_with(".zst") || fname.ends_with(".zstd") {
+            Codec::Zstd
+        } else if fname.ends_with(".lz4") {
+            Codec::Lz4
+        } else if fname.ends_with(".bz2") {
+            Codec::Bzip2
+        } else {
+            Codec::None
+        }
+    }
+
+    /// sniff the codec from magic bytes, restoring the read position
+    fn detect(fname: &str, file: &mut File) -> io::Result<Codec> {
+        let mut magic = [0u8; 4];
+        let n = file.read(&mut magic)?;
+        file.seek(SeekFrom::Start(0))?;
+        match Self::from_magic(&magic[..n]) {
+            Some(codec) => Ok(codec),
+            None => Ok(Self::from_extension(fname)),
+        }
+    }
+
+    fn reader(self, file: File) -> Box<dyn BufRead + Send> {
+        match self {
+            // MultiGzDecoder handles multi-member gzip (bgzip/pigz --independent)
+            Codec::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+            Codec::Zstd => Box::new(BufReader::new(
+                zstd::stream::read::Decoder::new(file).expect("invalid zstd stream"),
+            )),
+            Codec::Lz4 => Box::new(BufReader::new(
+                lz4::Decoder::new(file).expect("invalid lz4 stream"),
+            )),
+            Codec::Bzip2 => Box::new(BufReader::new(bzip2::read::BzDecoder::new(file))),
+            Codec::None => Box::new(BufReader::new(file)),
+        }
+    }
+}
+
+/// open `fname`, decompressing per `codec_override` or autodetection
+fn codec_reader(fname: &str, codec_override: Option<Codec>) -> Box<dyn BufRead + Send> {
+    let mut file = match File::open(fname) {
+        Ok(f) => f,
+        Err(_err) => {
+            panic!("unable to open file: {}", fname);
+        }
+    };
+    let codec = match codec_override {
+        Some(c) => c,
+        None => Codec::detect(fname, &mut file).unwrap_or(Codec::None),
+    };
+    codec.reader(file)
+}
+
+/// sidecar random-access index for gzip inputs, indexed at gzip *member*
+/// boundaries since flate2 doesn't expose bit-exact DEFLATE decoder state.
+/// `bit_offset`/`window` are unused placeholders for a future bit-exact indexer.
+struct GzipIndexEntry {
+    line_number: u64,
+    compressed_offset: u64,
+    bit_offset: u8,
+    window: Vec<u8>,
+}
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+fn skip_cstring(file: &mut File) -> io::Result<u64> {
+    let mut byte = [0u8; 1];
+    let mut n = 0u64;
+    loop {
+        file.read_exact(&mut byte)?;
+        n += 1;
+        if byte[0] == 0 {
+            break;
+        }
+    }
+    Ok(n)
+}
+
+/// Parse (and skip past) the gzip member header at the file's current
+/// position, returning its length in bytes.
+fn skip_gzip_header(file: &mut File) -> io::Result<u64> {
+    let mut header = [0u8; 10];
+    file.read_exact(&mut header)?;
+    if header[0..2] != GZIP_MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not a gzip member"));
+    }
+    let flags = header[3];
+    let mut len = 10u64;
+
+    if flags & 0x04 != 0 {
+        // FEXTRA
+        let mut xlen_buf = [0u8; 2];
+        file.read_exact(&mut xlen_buf)?;
+        let xlen = u16::from_le_bytes(xlen_buf) as i64;
+        file.seek(SeekFrom::Current(xlen))?;
+        len += 2 + xlen as u64;
+    }
+    if flags & 0x08 != 0 {
+        // FNAME
+        len += skip_cstring(file)?;
+    }
+    if flags & 0x10 != 0 {
+        // FCOMMENT
+        len += skip_cstring(file)?;
+    }
+    if flags & 0x02 != 0 {
+        // FHCRC
+        file.seek(SeekFrom::Current(2))?;
+        len += 2;
+    }
+
+    Ok(len)
+}
+
+/// Starting at the gzip member whose header begins at the file's current
+/// position, find the member's total length (header + deflate body + 8-byte
+/// CRC32/ISIZE trailer) without decompressing its contents.
+fn gzip_member_len(file: &mut File) -> io::Result<u64> {
+    let header_len = skip_gzip_header(file)?;
+
+    let mut decompress = Decompress::new(false);
+    let mut in_buf = [0u8; 8192];
+    let mut out_buf = [0u8; 65536];
+
+    loop {
+        let n = file.read(&mut in_buf)?;
+        if n == 0 {
+            return Ok(header_len + decompress.total_in());
+        }
+
+        let mut consumed = 0usize;
+        while consumed < n {
+            let before_in = decompress.total_in();
+            let status = decompress
+                .decompress(&in_buf[consumed..n], &mut out_buf, FlushDecompress::None)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+            consumed += (decompress.total_in() - before_in) as usize;
+
+            if status == Status::StreamEnd {
+                // Un-read whatever of this chunk belonged to the next member.
+                file.seek(SeekFrom::Current(-((n - consumed) as i64)))?;
+                let mut trailer = [0u8; 8];
+                file.read_exact(&mut trailer)?;
+                return Ok(header_len + decompress.total_in() + 8);
+            }
+        }
+    }
+}
+
+/// Walk `fname` member by member, recording a sync point at the start of
+/// each member with the line number it begins at.
+fn build_gzip_index(fname: &str) -> io::Result<Vec<GzipIndexEntry>> {
+    let mut file = File::open(fname)?;
+    let len = file.metadata()?.len();
+
+    let mut entries = Vec::new();
+    let mut offset = 0u64;
+    let mut line_number = 0u64;
+
+    while offset < len {
+        entries.push(GzipIndexEntry {
+            line_number,
+            compressed_offset: offset,
+            bit_offset: 0,
+            window: Vec::new(),
+        });
+
+        file.seek(SeekFrom::Start(offset))?;
+        let member_len = gzip_member_len(&mut file)?;
+
+        file.seek(SeekFrom::Start(offset))?;
+        let member_reader = BufReader::new(file.try_clone()?).take(member_len);
+        let mut lines = BufReader::new(GzDecoder::new(member_reader));
+        let mut buf = String::new();
+        loop {
+            buf.clear();
+            let n = lines.read_line(&mut buf)?;
+            if n == 0 {
+                break;
+            }
+            line_number += 1;
+        }
+
+        offset += member_len;
+    }
+
+    Ok(entries)
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 != 0 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+fn gzip_index_path(fname: &str) -> String {
+    format!("{}.idx", fname)
+}
+
+fn write_gzip_index(entries: &Vec<GzipIndexEntry>, path: &str) -> io::Result<()> {
+    let mut f = File::create(path)?;
+    for e in entries {
+        writeln!(
+            f,
+            "{}\t{}\t{}\t{}",
+            e.line_number,
+            e.compressed_offset,
+            e.bit_offset,
+            hex_encode(&e.window)
+        )?;
+    }
+    Ok(())
+}
+
+fn read_gzip_index(path: &str) -> io::Result<Vec<GzipIndexEntry>> {
+    let content = std::fs::read_to_string(path)?;
+    let mut entries = Vec::new();
+    for line in content.lines() {
+        let cols: Vec<&str> = line.splitn(4, '\t').collect();
+        if cols.len() != 4 {
+            continue;
+        }
+        entries.push(GzipIndexEntry {
+            line_number: cols[0].parse().unwrap_or(0),
+            compressed_offset: cols[1].parse().unwrap_or(0),
+            bit_offset: cols[2].parse().unwrap_or(0),
+            window: hex_decode(cols[3]).unwrap_or_default(),
+        });
+    }
+    Ok(entries)
+}
+
+/// Parse a `START:COUNT` `--range` spec.
+fn parse_range(spec: &str) -> (u64, u64) {
+    let mut parts = spec.splitn(2, ':');
+    let start = parts.next().and_then(|s| s.parse().ok()).unwrap_or(0);
+    let count = parts.next().and_then(|s| s.parse().ok()).unwrap_or(u64::MAX);
+    (start, count)
+}
+
+/// Stream `count` lines of `fname` starting at line `start`, using the
+/// sidecar `.idx` if one exists to seek near `start` instead of
+/// decompressing from the top. `codec_override` takes precedence over
+/// autodetection, same as `codec_reader`.
+fn ranged_gzip_lines(
+    fname: &str,
+    start: u64,
+    count: u64,
+    codec_override: Option<Codec>,
+) -> Box<dyn Iterator<Item = String> + Send> {
+    let mut file = File::open(fname).unwrap_or_else(|_| panic!("unable to open file: {}", fname));
+    let codec = match codec_override {
+        Some(c) => c,
+        None => Codec::detect(fname, &mut file).unwrap_or(Codec::None),
+    };
+    if codec != Codec::Gzip {
+        panic!(
+            "--range only supports gzip input, but {} looks like {:?}",
+            fname, codec
+        );
+    }
+
+    let sync_point = read_gzip_index(&gzip_index_path(fname))
+        .ok()
+        .and_then(|entries| entries.into_iter().filter(|e| e.line_number <= start).last());
+
+    let base_line = match &sync_point {
+        Some(e) => {
+            file.seek(SeekFrom::Start(e.compressed_offset))
+                .expect("seek failed");
+            e.line_number
+        }
+        None => 0,
+    };
+
+    // multi-member gzip may still span the remaining stream
+    let reader = BufReader::new(MultiGzDecoder::new(file));
+    let to_skip = (start - base_line) as usize;
+    Box::new(
+        reader
+            .lines()
+            .filter(|l| l.is_ok())
+            .map(|l| l.unwrap())
+            .skip(to_skip)
+            .take(count as usize),
+    )
+}
+
 fn extract_fields(
     doc: &Element,
     pointers: &Vec<&str>,
@@ -41,10 +381,13 @@ fn unquote_str<'a>(el_type: ElementType, s: &'a String) -> &'a str {
     }
 }
 
+#[derive(Clone, Copy)]
 enum FormatType {
     Json,
     Tab,
     Space,
+    Csv,
+    Tsv,
 }
 
 impl FormatType {
@@ -53,18 +396,197 @@ impl FormatType {
             "json" => Ok(FormatType::Json),
             "tab" => Ok(FormatType::Tab),
             "space" => Ok(FormatType::Space),
+            "csv" => Ok(FormatType::Csv),
+            "tsv" => Ok(FormatType::Tsv),
             _ => Err("unknown format type"),
         }
     }
 }
 
+/// Quote `field` per RFC 4180 if it contains the delimiter, a double quote,
+/// or a line break, doubling any embedded quotes.
+fn csv_quote(field: &str, delim: char) -> String {
+    if field.contains(delim) || field.contains('"') || field.contains('\r') || field.contains('\n') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn format_delimited(fields: &Vec<String>, delim: char) -> String {
+    fields
+        .iter()
+        .map(|f| csv_quote(f, delim))
+        .collect::<Vec<String>>()
+        .join(&delim.to_string())
+}
+
+fn format_fields(fields: &Vec<String>, format_type: FormatType) -> String {
+    match format_type {
+        FormatType::Tab => fields.join("\t"),
+        FormatType::Json => format!("[{}]", fields.join(",")),
+        FormatType::Space => fields.join(" "),
+        FormatType::Csv => format_delimited(fields, ','),
+        FormatType::Tsv => format_delimited(fields, '\t'),
+    }
+}
+
+/// Derive a column name for a JSON-pointer field, used by `--header`: the
+/// trailing pointer segment, e.g. `/a/b` -> `b`.
+fn pointer_label(pointer: &str) -> String {
+    pointer.rsplit('/').next().unwrap_or(pointer).to_string()
+}
+
+/// Outcome of running a single line through the parser, returned by
+/// `process_line` so a batch's results can be reassembled in order without
+/// interleaving stdout writes from multiple rayon threads.
+enum LineOutcome {
+    Row(String),
+    Error(String),
+    Skip,
+}
+
+/// Destination for lines that failed to parse or were missing a requested
+/// pointer, so they can be quarantined instead of silently dropped.
+enum ErrorSink {
+    Stderr,
+    Stdout,
+    File(File),
+}
+
+impl ErrorSink {
+    fn open(spec: &str) -> io::Result<ErrorSink> {
+        match spec {
+            "-" => Ok(ErrorSink::Stdout),
+            "stderr" => Ok(ErrorSink::Stderr),
+            path => Ok(ErrorSink::File(File::create(path)?)),
+        }
+    }
+
+    fn write_line(&mut self, line: &str) {
+        match self {
+            ErrorSink::Stderr => eprintln!("{}", line),
+            ErrorSink::Stdout => println!("{}", line),
+            ErrorSink::File(f) => {
+                let _ = writeln!(f, "{}", line);
+            }
+        }
+    }
+}
+
+thread_local! {
+    static SELECT_PARSER: RefCell<simdjson_rust::dom::Parser> =
+        RefCell::new(simdjson_rust::dom::Parser::default());
+}
+
+fn process_line(
+    line: &str,
+    pointers: &Vec<&str>,
+    drop_quotes: bool,
+    verbosity: u32,
+    format_type: FormatType,
+) -> LineOutcome {
+    if line.is_empty() {
+        return LineOutcome::Skip;
+    }
+
+    SELECT_PARSER.with(|cell| {
+        let mut parser = cell.borrow_mut();
+        let doc = match parser.parse(line) {
+            Ok(val) => val,
+            Err(_e) => {
+                if verbosity > 0 {
+                    eprintln!("parse error on line: {}", line);
+                }
+                return LineOutcome::Error(line.to_string());
+            }
+        };
+
+        match extract_fields(&doc, pointers, drop_quotes) {
+            Ok(fields) => LineOutcome::Row(format_fields(&fields, format_type)),
+            Err(_e) => {
+                if verbosity > 0 {
+                    eprintln!("missing field on line: {}", line);
+                }
+                LineOutcome::Error(line.to_string())
+            }
+        }
+    })
+}
+
+/// Reassembles seq-tagged batches -- which may arrive out of order, since
+/// rayon workers finish batches in whatever order they happen to complete --
+/// back into the original input order.
+struct Reassembler<T> {
+    pending: BTreeMap<u64, Vec<T>>,
+    expected: u64,
+}
+
+impl<T> Reassembler<T> {
+    fn new() -> Self {
+        Reassembler {
+            pending: BTreeMap::new(),
+            expected: 0,
+        }
+    }
+
+    /// Feed one seq-tagged batch, returning (in order) any batches that are
+    /// now ready to emit.
+    fn push(&mut self, seq: u64, items: Vec<T>) -> Vec<T> {
+        self.pending.insert(seq, items);
+        let mut ready = Vec::new();
+        while let Some(items) = self.pending.remove(&self.expected) {
+            ready.extend(items);
+            self.expected += 1;
+        }
+        ready
+    }
+}
+
+const SELECT_BATCH_SIZE: usize = 1000;
+
+/// Upper bound on batches that may be queued on rayon at once. `rayon::spawn`
+/// itself has no backpressure -- it just enqueues and returns -- so without
+/// this cap a producer reading faster than the printer drains `rx` would
+/// pile up unbounded `Vec<String>` batches in rayon's internal queue.
+const SELECT_MAX_INFLIGHT_BATCHES: usize = 32;
+
+/// Process one batch of lines on rayon's thread pool and send the
+/// (still batch-ordered) results back tagged with `seq`, so the printer can
+/// reassemble the overall input order even though batches finish out of
+/// order across worker threads. `permit_tx` returns this batch's inflight
+/// slot once the batch is done, for the producer's `SELECT_MAX_INFLIGHT_BATCHES` gate.
+fn spawn_batch(
+    lines: Vec<String>,
+    pointers: Vec<String>,
+    drop_quotes: bool,
+    verbosity: u32,
+    format_type: FormatType,
+    seq: u64,
+    tx: SyncSender<(u64, Vec<LineOutcome>)>,
+    permit_tx: SyncSender<()>,
+) {
+    rayon::spawn(move || {
+        let pointer_refs: Vec<&str> = pointers.iter().map(|p| p.as_str()).collect();
+        let outcomes: Vec<LineOutcome> = lines
+            .par_iter()
+            .map(|line| process_line(line, &pointer_refs, drop_quotes, verbosity, format_type))
+            .collect();
+        tx.send((seq, outcomes)).unwrap();
+        let _ = permit_tx.send(());
+    });
+}
+
 fn extract(
-    input: impl Iterator<Item = String>,
+    input: impl Iterator<Item = String> + Send + 'static,
     pointers: &Vec<&str>,
     drop_quotes: bool,
     suppress_errors: bool,
     verbosity: u32,
     format_type: FormatType,
+    errors_to: Option<Arc<Mutex<ErrorSink>>>,
+    max_error_rate: Option<f64>,
+    header: bool,
 ) -> bool {
     if pointers.len() == 0 {
         panic!("extract needs pointers");
@@ -76,41 +598,109 @@ fn extract(
         _ => drop_quotes,
     };
 
-    let mut error_count = 0;
-    let mut parser = simdjson_rust::dom::Parser::default();
-    for line in input {
-        if line.is_empty() {
-            continue;
-        }
-        let doc = parser.parse(&line);
-        let doc = match doc {
-            Ok(val) => val,
-            Err(_e) => {
-                error_count += 1;
-                if verbosity > 0 {
-                    eprintln!("parse error on line: {}", line);
-                }
-                continue;
-            }
+    if header {
+        let labels: Vec<String> = pointers.iter().map(|p| pointer_label(p)).collect();
+        let labels = match format_type {
+            // data rows are already-JSON values; quote the raw labels to match.
+            FormatType::Json => labels
+                .iter()
+                .map(|l| format!("\"{}\"", json_escape(l)))
+                .collect(),
+            _ => labels,
         };
+        println!("{}", format_fields(&labels, format_type));
+    }
 
-        let fields = extract_fields(&doc, pointers, drop_quotes);
-        let fields = match fields {
-            Ok(f) => f,
-            Err(_e) => {
-                error_count += 1;
-                if verbosity > 0 {
-                    eprintln!("missing field on line: {}", line);
+    let pointers: Vec<String> = pointers.iter().map(|p| p.to_string()).collect();
+
+    let (tx, rx) = sync_channel::<(u64, Vec<LineOutcome>)>(100);
+    // Preloaded with `SELECT_MAX_INFLIGHT_BATCHES` permits; the producer
+    // blocks on `permit_rx.recv()` before spawning another batch, and each
+    // batch returns its permit via `permit_tx` once rayon finishes it.
+    let (permit_tx, permit_rx) = sync_channel::<()>(SELECT_MAX_INFLIGHT_BATCHES);
+    for _ in 0..SELECT_MAX_INFLIGHT_BATCHES {
+        permit_tx.send(()).unwrap();
+    }
+    let th = thread::spawn(move || {
+        let mut input = input;
+        let mut batch = Vec::with_capacity(SELECT_BATCH_SIZE);
+        let mut seq: u64 = 0;
+        while let Some(line) = input.next() {
+            batch.push(line);
+            if batch.len() >= SELECT_BATCH_SIZE {
+                let this_batch = std::mem::replace(&mut batch, Vec::with_capacity(SELECT_BATCH_SIZE));
+                permit_rx.recv().unwrap();
+                spawn_batch(
+                    this_batch,
+                    pointers.clone(),
+                    drop_quotes,
+                    verbosity,
+                    format_type,
+                    seq,
+                    tx.clone(),
+                    permit_tx.clone(),
+                );
+                seq += 1;
+            }
+        }
+        if !batch.is_empty() {
+            permit_rx.recv().unwrap();
+            spawn_batch(
+                batch,
+                pointers.clone(),
+                drop_quotes,
+                verbosity,
+                format_type,
+                seq,
+                tx.clone(),
+                permit_tx.clone(),
+            );
+        }
+    });
+
+    // Rejected lines are written to `errors_to` here, in the same
+    // seq-ordered reassembly as the extracted rows, so a `--errors-to -`
+    // stream doesn't interleave out of input order with stdout.
+    let mut reassembler = Reassembler::new();
+    let mut error_count = 0;
+    let mut total = 0;
+    for (seq, outcomes) in rx {
+        for outcome in reassembler.push(seq, outcomes) {
+            match outcome {
+                LineOutcome::Row(s) => {
+                    println!("{}", s);
+                    total += 1;
                 }
-                continue;
+                LineOutcome::Error(line) => {
+                    if let Some(sink) = &errors_to {
+                        sink.lock().unwrap().write_line(&line);
+                    }
+                    error_count += 1;
+                    total += 1;
+                }
+                LineOutcome::Skip => {}
             }
-        };
+        }
+    }
+    th.join().unwrap();
 
-        match format_type {
-            FormatType::Tab => println!("{}", fields.join("\t")),
-            FormatType::Json => println!("[{}]", fields.join(",")),
-            _ => println!("{}", fields.join(" ")),
+    if let Some(threshold) = max_error_rate {
+        let rate = if total == 0 {
+            0.0
+        } else {
+            error_count as f64 / total as f64
         };
+        if rate > threshold {
+            eprintln!(
+                "{} of {} line(s) rejected ({:.1}%), exceeding threshold of {:.1}%",
+                error_count,
+                total,
+                rate * 100.0,
+                threshold * 100.0
+            );
+            return false;
+        }
+        return true;
     }
 
     if error_count > 0 {
@@ -125,6 +715,231 @@ fn extract(
     return true;
 }
 
+/// A single per-pointer rule for `validate`, e.g. `/age:range:0..150`.
+struct Rule {
+    pointer: String,
+    constraint: Constraint,
+}
+
+enum Constraint {
+    Required,
+    Type(String),
+    NonEmpty,
+    Matches(Regex),
+    Range(f64, f64),
+}
+
+fn element_type_name(t: ElementType) -> &'static str {
+    match t {
+        ElementType::String => "string",
+        ElementType::Int64 | ElementType::UInt64 | ElementType::Double => "number",
+        ElementType::Boolean => "bool",
+        ElementType::Array => "array",
+        ElementType::Object => "object",
+        ElementType::Null => "null",
+    }
+}
+
+fn parse_constraint(spec: &str) -> Result<Constraint, String> {
+    if spec == "required" {
+        return Ok(Constraint::Required);
+    }
+    if spec == "nonempty" {
+        return Ok(Constraint::NonEmpty);
+    }
+    if let Some(rest) = spec.strip_prefix("type:") {
+        return Ok(Constraint::Type(rest.to_string()));
+    }
+    if let Some(rest) = spec.strip_prefix("matches:") {
+        let re = Regex::new(rest).map_err(|e| format!("invalid regex '{}': {}", rest, e))?;
+        return Ok(Constraint::Matches(re));
+    }
+    if let Some(rest) = spec.strip_prefix("range:") {
+        let bounds: Vec<&str> = rest.splitn(2, "..").collect();
+        if bounds.len() != 2 {
+            return Err(format!("invalid range spec: {}", rest));
+        }
+        let min: f64 = bounds[0]
+            .parse()
+            .map_err(|_| format!("invalid range min: {}", bounds[0]))?;
+        let max: f64 = bounds[1]
+            .parse()
+            .map_err(|_| format!("invalid range max: {}", bounds[1]))?;
+        return Ok(Constraint::Range(min, max));
+    }
+    Err(format!("unknown constraint: {}", spec))
+}
+
+fn parse_rule(spec: &str) -> Result<Rule, String> {
+    let mut parts = spec.splitn(2, ':');
+    let pointer = parts
+        .next()
+        .filter(|p| !p.is_empty())
+        .ok_or_else(|| format!("invalid rule, missing pointer: {}", spec))?
+        .to_string();
+    let constraint_spec = parts
+        .next()
+        .ok_or_else(|| format!("rule missing constraint: {}", spec))?;
+    let constraint = parse_constraint(constraint_spec)?;
+    Ok(Rule { pointer, constraint })
+}
+
+fn parse_rules(spec: &str) -> Result<Vec<Rule>, String> {
+    spec.split(',').filter(|s| !s.is_empty()).map(parse_rule).collect()
+}
+
+impl Rule {
+    /// Evaluate this rule against a parsed record, returning a human-readable
+    /// violation reason if it fails, or `None` if it passes (or the pointer
+    /// is simply absent and the rule isn't `required`).
+    fn check(&self, doc: &Element) -> Option<String> {
+        let el = doc.at_pointer(&self.pointer);
+        match el {
+            Err(_e) => match self.constraint {
+                Constraint::Required => Some("required field missing".to_string()),
+                _ => None,
+            },
+            Ok(v) => match &self.constraint {
+                Constraint::Required => None,
+                Constraint::Type(expected) => {
+                    let actual = element_type_name(v.get_type());
+                    if actual == expected {
+                        None
+                    } else {
+                        Some(format!("expected type {}, got {}", expected, actual))
+                    }
+                }
+                Constraint::NonEmpty => {
+                    let s = unquote_str(v.get_type(), &v.minify()).to_string();
+                    if s.is_empty() || s == "[]" || s == "{}" || s == "null" {
+                        Some("value is empty".to_string())
+                    } else {
+                        None
+                    }
+                }
+                Constraint::Matches(re) => {
+                    let s = unquote_str(v.get_type(), &v.minify()).to_string();
+                    if re.is_match(&s) {
+                        None
+                    } else {
+                        Some(format!("value does not match /{}/", re.as_str()))
+                    }
+                }
+                Constraint::Range(min, max) => match v.minify().parse::<f64>() {
+                    Ok(n) if n >= *min && n <= *max => None,
+                    Ok(n) => Some(format!("value {} out of range {}..{}", n, min, max)),
+                    Err(_e) => Some("value is not numeric".to_string()),
+                },
+            },
+        }
+    }
+}
+
+struct Diagnostic {
+    line_no: usize,
+    pointer: String,
+    reason: String,
+}
+
+/// Escape a string for embedding in a JSON string literal. `{:?}` (Rust's
+/// Debug format) is close but not the same thing -- e.g. it emits
+/// `\u{1f}`-style braced escapes for control characters, which isn't valid
+/// JSON -- so diagnostics that need to round-trip through a JSON parser
+/// need their own escaping.
+fn json_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// One JSON object per violating record, not per violation: `diagnostics`
+/// must already be in ascending line_no order, so consecutive runs that
+/// share a line_no are grouped instead of printed one violation at a time.
+fn diagnostics_to_json(diagnostics: &[Diagnostic]) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut i = 0;
+    while i < diagnostics.len() {
+        let line_no = diagnostics[i].line_no;
+        let mut j = i;
+        while j < diagnostics.len() && diagnostics[j].line_no == line_no {
+            j += 1;
+        }
+        let violations: Vec<String> = diagnostics[i..j]
+            .iter()
+            .map(|d| {
+                format!(
+                    "{{\"pointer\":\"{}\",\"reason\":\"{}\"}}",
+                    json_escape(&d.pointer),
+                    json_escape(&d.reason)
+                )
+            })
+            .collect();
+        lines.push(format!(
+            "{{\"line\":{},\"violations\":[{}]}}",
+            line_no,
+            violations.join(",")
+        ));
+        i = j;
+    }
+    lines
+}
+
+fn validate(input: impl Iterator<Item = String>, rules: &Vec<Rule>, format_type: &str) -> bool {
+    let mut parser = simdjson_rust::dom::Parser::default();
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for (i, line) in input.enumerate() {
+        let line_no = i + 1;
+        if line.is_empty() {
+            continue;
+        }
+
+        let doc = match parser.parse(&line) {
+            Ok(val) => val,
+            Err(_e) => {
+                diagnostics.push(Diagnostic {
+                    line_no,
+                    pointer: String::new(),
+                    reason: "invalid JSON".to_string(),
+                });
+                continue;
+            }
+        };
+
+        for rule in rules {
+            if let Some(reason) = rule.check(&doc) {
+                diagnostics.push(Diagnostic {
+                    line_no,
+                    pointer: rule.pointer.clone(),
+                    reason,
+                });
+            }
+        }
+    }
+
+    if format_type == "json" {
+        for line in diagnostics_to_json(&diagnostics) {
+            println!("{}", line);
+        }
+    } else {
+        for d in &diagnostics {
+            println!("line {}: {}: {}", d.line_no, d.pointer, d.reason);
+        }
+    }
+
+    diagnostics.is_empty()
+}
+
 fn stdin_input() -> impl Iterator<Item = String> {
     let file = io::stdin();
     let reader = BufReader::new(file);
@@ -137,14 +952,15 @@ fn stdin_input() -> impl Iterator<Item = String> {
 }
 
 fn file_input(fname: String) -> impl Iterator<Item = String> {
-    let file = match File::open(&fname) {
+    let mut file = match File::open(&fname) {
         Ok(f) => f,
         Err(_err) => {
             println!("unable to open file {}", fname);
             panic!("missing input file");
         }
     };
-    let reader = BufReader::new(file);
+    let codec = Codec::detect(&fname, &mut file).unwrap_or(Codec::None);
+    let reader = codec.reader(file);
 
     let iter = reader
         .lines()
@@ -158,18 +974,12 @@ fn files_input(fnames: Vec<String>) -> impl Iterator<Item = String> {
     iters.flat_map(|it| it)
 }
 
-fn zline_files(fnames: &Vec<String>) -> bool {
+fn zline_files(fnames: &Vec<String>, codec_override: Option<Codec>) -> bool {
     let fnames = fnames.clone();
     let (tx, rx) = sync_channel(1000);
-    let th = thread::spawn(|| {
+    let th = thread::spawn(move || {
         fnames.into_par_iter().for_each_with(tx, |s, fname| {
-            let file = match File::open(&fname) {
-                Ok(f) => f,
-                Err(_err) => {
-                    panic!("unable to open file: {}", fname);
-                }
-            };
-            let reader = BufReader::new(GzDecoder::new(file));
+            let reader = codec_reader(&fname, codec_override);
 
             let iter = reader
                 .lines()
@@ -207,6 +1017,10 @@ enum SubCommand {
     Zline(ZlineOpts),
     /// field selector
     Select(SelectOpts),
+    /// schema-validation linter for JSONL records
+    Validate(ValidateOpts),
+    /// build a sidecar random-access index for a gzip file
+    Index(IndexOpts),
 }
 
 #[derive(Clap)]
@@ -217,6 +1031,13 @@ struct ZlineOpts {
     /// parallelism (by default uses all cores)
     #[clap(short, default_value("0"))]
     p: usize,
+    /// override codec autodetection (gzip, zstd, lz4, bzip2, none)
+    #[clap(long, possible_values(&["gzip", "zstd", "lz4", "bzip2", "none"]))]
+    codec: Option<String>,
+    /// only emit lines START..START+COUNT, using a sidecar .idx built by
+    /// `spac index` to seek near START if one exists
+    #[clap(long)]
+    range: Option<String>,
 }
 
 #[derive(Clap)]
@@ -236,8 +1057,46 @@ struct SelectOpts {
     #[clap(short, long, parse(from_occurrences))]
     verbose: u32,
     /// use tab separated format output
-    #[clap(long, possible_values(&["space", "tab", "json"]), default_value("space"))]
+    #[clap(long, possible_values(&["space", "tab", "json", "csv", "tsv"]), default_value("space"))]
     format: String,
+    /// write lines that failed to parse or were missing a field here instead of
+    /// discarding them (a path, `-` for stdout, or `stderr`)
+    #[clap(long)]
+    errors_to: Option<String>,
+    /// only exit non-zero if the fraction of rejected lines exceeds this
+    /// threshold (0.0-1.0); by default any rejected line fails the run
+    #[clap(long)]
+    max_error_rate: Option<f64>,
+    /// emit a header row of column names (the trailing pointer segment of
+    /// each field) before the extracted rows
+    #[clap(long)]
+    header: bool,
+    /// only read lines START..START+COUNT from each (gzip) input, using a
+    /// sidecar .idx built by `spac index` to seek near START if one exists
+    #[clap(long)]
+    range: Option<String>,
+}
+
+#[derive(Clap)]
+struct ValidateOpts {
+    /// input files, leave blank for stdin
+    input: Vec<String>,
+    /// comma separated rule spec, e.g. "/id:required,/age:range:0..150"
+    #[clap(short, long)]
+    rules: Option<String>,
+    /// read the rule spec from a file instead of the command line
+    #[clap(long)]
+    rules_file: Option<String>,
+    /// diagnostic output format
+    #[clap(long, possible_values(&["text", "json"]), default_value("text"))]
+    format: String,
+}
+
+#[derive(Clap)]
+struct IndexOpts {
+    /// gzip input files to build a sidecar .idx for
+    #[clap(required(true))]
+    input: Vec<String>,
 }
 
 fn run_app() -> bool {
@@ -251,13 +1110,48 @@ fn run_app() -> bool {
                     .build_global()
                     .unwrap();
             }
-            zline_files(&inputs)
+            let codec = opts.codec.map(|c| Codec::from_str(&c).unwrap());
+            if let Some(range) = opts.range {
+                let (start, count) = parse_range(&range);
+                for fname in &inputs {
+                    for line in ranged_gzip_lines(fname, start, count, codec) {
+                        println!("{}", line);
+                    }
+                }
+                return true;
+            }
+            zline_files(&inputs, codec)
         }
         SubCommand::Select(opts) => {
             let pointers: Vec<&str> = opts.fields.split(",").collect();
             if opts.raw && opts.format == "json" {
                 eprintln!("warning: --raw has no effect when using json formatting")
             }
+            let errors_to = opts.errors_to.map(|spec| {
+                Arc::new(Mutex::new(
+                    ErrorSink::open(&spec).expect("unable to open --errors-to sink"),
+                ))
+            });
+            if let Some(range) = opts.range {
+                let (start, count) = parse_range(&range);
+                let fnames = opts.input.clone();
+                let input: Box<dyn Iterator<Item = String> + Send> = Box::new(
+                    fnames
+                        .into_iter()
+                        .flat_map(move |fname| ranged_gzip_lines(&fname, start, count, None)),
+                );
+                return extract(
+                    input,
+                    &pointers,
+                    opts.raw,
+                    opts.quiet,
+                    opts.verbose,
+                    FormatType::from(&opts.format).unwrap(),
+                    errors_to,
+                    opts.max_error_rate,
+                    opts.header,
+                );
+            }
             if opts.input.len() == 0 {
                 return extract(
                     stdin_input(),
@@ -266,6 +1160,9 @@ fn run_app() -> bool {
                     opts.quiet,
                     opts.verbose,
                     FormatType::from(&opts.format).unwrap(),
+                    errors_to,
+                    opts.max_error_rate,
+                    opts.header,
                 );
             } else {
                 return extract(
@@ -275,12 +1172,201 @@ fn run_app() -> bool {
                     opts.quiet,
                     opts.verbose,
                     FormatType::from(&opts.format).unwrap(),
+                    errors_to,
+                    opts.max_error_rate,
+                    opts.header,
                 );
             }
         }
+        SubCommand::Validate(opts) => {
+            let spec = match (opts.rules, opts.rules_file) {
+                (Some(r), _) => r,
+                (None, Some(path)) => {
+                    std::fs::read_to_string(&path)
+                        .expect("unable to read --rules-file")
+                        .trim()
+                        .to_string()
+                }
+                (None, None) => panic!("validate needs rules (-r or --rules-file)"),
+            };
+            let rules = parse_rules(&spec).unwrap_or_else(|e| panic!("{}", e));
+            if opts.input.len() == 0 {
+                validate(stdin_input(), &rules, &opts.format)
+            } else {
+                validate(files_input(opts.input), &rules, &opts.format)
+            }
+        }
+        SubCommand::Index(opts) => {
+            let mut ok = true;
+            for fname in opts.input {
+                match build_gzip_index(&fname) {
+                    Ok(entries) => {
+                        let idx_path = gzip_index_path(&fname);
+                        if let Err(e) = write_gzip_index(&entries, &idx_path) {
+                            eprintln!("failed to write index for {}: {}", fname, e);
+                            ok = false;
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("failed to index {}: {}", fname, e);
+                        ok = false;
+                    }
+                }
+            }
+            ok
+        }
     }
 }
 
 fn main() {
     std::process::exit(if run_app() { 0 } else { 1 });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write as _;
+
+    #[test]
+    fn csv_quote_leaves_plain_fields_alone() {
+        assert_eq!(csv_quote("hello", ','), "hello");
+    }
+
+    #[test]
+    fn csv_quote_wraps_fields_containing_the_delimiter() {
+        assert_eq!(csv_quote("a,b", ','), "\"a,b\"");
+    }
+
+    #[test]
+    fn csv_quote_doubles_embedded_quotes() {
+        assert_eq!(csv_quote("say \"hi\"", ','), "\"say \"\"hi\"\"\"");
+    }
+
+    #[test]
+    fn csv_quote_wraps_fields_containing_newlines() {
+        assert_eq!(csv_quote("a\nb", ','), "\"a\nb\"");
+        assert_eq!(csv_quote("a\rb", ','), "\"a\rb\"");
+    }
+
+    #[test]
+    fn csv_quote_respects_the_given_delimiter() {
+        // A comma shouldn't force quoting when the delimiter is a tab.
+        assert_eq!(csv_quote("a,b", '\t'), "a,b");
+        assert_eq!(csv_quote("a\tb", '\t'), "\"a\tb\"");
+    }
+
+    #[test]
+    fn format_delimited_quotes_only_fields_that_need_it() {
+        let fields = vec!["plain".to_string(), "has,comma".to_string()];
+        assert_eq!(format_delimited(&fields, ','), "plain,\"has,comma\"");
+    }
+
+    #[test]
+    fn reassembler_preserves_seq_order_when_batches_arrive_out_of_order() {
+        let mut r = Reassembler::new();
+        // seq 1 arrives before seq 0 -- as rayon workers finishing out of order.
+        assert_eq!(r.push(1, vec!["c", "d"]), Vec::<&str>::new());
+        assert_eq!(r.push(0, vec!["a", "b"]), vec!["a", "b", "c", "d"]);
+        assert_eq!(r.push(2, vec!["e"]), vec!["e"]);
+    }
+
+    #[test]
+    fn reassembler_keeps_rows_and_errors_in_seq_order() {
+        // Mirrors extract()'s use: batch 1 (with a rejected line) completes
+        // before batch 0 does, but the sink/stdout writes that follow must
+        // still see them in original input order.
+        let mut r = Reassembler::new();
+        assert!(r
+            .push(1, vec![LineOutcome::Error("bad1".to_string())])
+            .is_empty());
+        let ready = r.push(0, vec![LineOutcome::Row("good0".to_string())]);
+        let labels: Vec<&str> = ready
+            .iter()
+            .map(|o| match o {
+                LineOutcome::Row(s) => s.as_str(),
+                LineOutcome::Error(s) => s.as_str(),
+                LineOutcome::Skip => "skip",
+            })
+            .collect();
+        assert_eq!(labels, vec!["good0", "bad1"]);
+    }
+
+    #[test]
+    fn diagnostics_to_json_groups_violations_by_line_no() {
+        let diagnostics = vec![
+            Diagnostic {
+                line_no: 1,
+                pointer: "/a".to_string(),
+                reason: "invalid JSON".to_string(),
+            },
+            Diagnostic {
+                line_no: 2,
+                pointer: "/a".to_string(),
+                reason: "required".to_string(),
+            },
+            Diagnostic {
+                line_no: 2,
+                pointer: "/b".to_string(),
+                reason: "value \"x\" out of range".to_string(),
+            },
+        ];
+        let lines = diagnostics_to_json(&diagnostics);
+        assert_eq!(
+            lines,
+            vec![
+                "{\"line\":1,\"violations\":[{\"pointer\":\"/a\",\"reason\":\"invalid JSON\"}]}",
+                "{\"line\":2,\"violations\":[{\"pointer\":\"/a\",\"reason\":\"required\"},\
+                 {\"pointer\":\"/b\",\"reason\":\"value \\\"x\\\" out of range\"}]}",
+            ]
+        );
+    }
+
+    /// Writes `members`, each its own independent gzip stream, concatenated
+    /// back to back -- the `bgzip`/`pigz --independent` shape `build_gzip_index`
+    /// and `MultiGzDecoder` both need to handle.
+    fn write_multimember_gzip(path: &str, members: &[&str]) {
+        let mut file = File::create(path).unwrap();
+        for member in members {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            encoder.write_all(member.as_bytes()).unwrap();
+            let bytes = encoder.finish().unwrap();
+            file.write_all(&bytes).unwrap();
+        }
+    }
+
+    #[test]
+    fn gzip_index_build_and_read_round_trip() {
+        let path = std::env::temp_dir().join(format!(
+            "select-test-{:?}.gz",
+            std::thread::current().id()
+        ));
+        let path = path.to_str().unwrap().to_string();
+        write_multimember_gzip(&path, &["line1\nline2\n", "line3\n"]);
+
+        let entries = build_gzip_index(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].line_number, 0);
+        assert_eq!(entries[1].line_number, 2);
+
+        let idx_path = gzip_index_path(&path);
+        write_gzip_index(&entries, &idx_path).unwrap();
+        let read_back = read_gzip_index(&idx_path).unwrap();
+        assert_eq!(read_back.len(), entries.len());
+        for (a, b) in entries.iter().zip(read_back.iter()) {
+            assert_eq!(a.line_number, b.line_number);
+            assert_eq!(a.compressed_offset, b.compressed_offset);
+        }
+
+        let mut file = File::open(&path).unwrap();
+        let mut content = String::new();
+        MultiGzDecoder::new(&mut file)
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "line1\nline2\nline3\n");
+
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&idx_path).unwrap();
+    }
+}